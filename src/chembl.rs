@@ -24,11 +24,96 @@
 use std::io::prelude::*;
 use rand::prelude::*;
 
-type ChemblID = String;
+pub(crate) type ChemblID = String;
 type CanonicalSMILES = String;
 type StandardInchi = String;
 type StandardInchiKey = String;
 
+/// Errors that can occur while ingesting a ChEMBL source file. Malformed
+/// rows are reported rather than causing a panic, so a truncated line or a
+/// missing field in a multi-gigabyte EBI dump doesn't abort the whole load.
+#[derive(Debug)]
+pub enum SourceError {
+    Io(std::io::Error),
+    MalformedRecord { line_no: usize, content: String },
+    EmptyField { line_no: usize, field: &'static str }
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SourceError::Io(e) => write!(f, "io error: {e}"),
+            SourceError::MalformedRecord { line_no, content } => write!(f, "malformed record at line {line_no}: {content}"),
+            SourceError::EmptyField { line_no, field } => write!(f, "empty field `{field}` at line {line_no}")
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<std::io::Error> for SourceError {
+    fn from(e: std::io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+/// The layout of a ChEMBL text export, auto-detected from its first line so
+/// the same reader can ingest both the tab-separated chemreps dump and a
+/// comma-separated CSV export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChemblFormat {
+    ChemrepsTsv,
+    Csv
+}
+
+impl ChemblFormat {
+    fn detect(header: &str) -> Self {
+        if header.contains(',') && !header.contains('\t') {
+            ChemblFormat::Csv
+        } else {
+            ChemblFormat::ChemrepsTsv
+        }
+    }
+
+    fn delimiter(&self) -> char {
+        match self {
+            ChemblFormat::ChemrepsTsv => '\t',
+            ChemblFormat::Csv => ','
+        }
+    }
+}
+
+/// Column positions of the four `EntryChembl` fields within a row, read
+/// from the header line so files don't have to agree on column order.
+struct ColumnOrder {
+    chembl_id: usize,
+    smiles: usize,
+    inchi: usize,
+    inchi_key: usize
+}
+
+impl ColumnOrder {
+    /// The column order of the chemreps dump when no header names it explicitly.
+    fn default_chemreps() -> Self {
+        Self { chembl_id: 0, smiles: 1, inchi: 2, inchi_key: 3 }
+    }
+
+    fn from_header(header: &str, delimiter: char) -> Option<Self> {
+        let (mut chembl_id, mut smiles, mut inchi, mut inchi_key) = (None, None, None, None);
+        for (i, col) in header.split(delimiter).enumerate() {
+            match col.trim().to_lowercase().as_str() {
+                "chembl_id" => chembl_id = Some(i),
+                "canonical_smiles" | "smiles" => smiles = Some(i),
+                "standard_inchi" | "inchi" => inchi = Some(i),
+                "standard_inchi_key" | "inchi_key" => inchi_key = Some(i),
+                _ => {}
+            }
+        }
+        Some(Self { chembl_id: chembl_id?, smiles: smiles?, inchi: inchi?, inchi_key: inchi_key? })
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct EntryChembl {
     pub chembl_id: ChemblID,
     pub smiles: CanonicalSMILES,
@@ -37,21 +122,119 @@ pub struct EntryChembl {
 }
 
 impl EntryChembl {
-    pub fn new(v: Vec<&str>) -> Self {
-        let (chembl_id, smiles, inchi, inchi_key) = (String::from(v[0]), String::from(v[1]), String::from(v[2]), String::from(v[3]));
-        Self { chembl_id, smiles, inchi, inchi_key }
+    fn try_new(v: &[&str], format: &ChemblFormat, order: &ColumnOrder, line_no: usize) -> Result<Self, SourceError> {
+        let delimiter = format.delimiter().to_string();
+        let field = |idx: usize, name: &'static str| -> Result<String, SourceError> {
+            let raw = *v.get(idx).ok_or_else(|| SourceError::MalformedRecord { line_no, content: v.join(&delimiter) })?;
+            if raw.is_empty() {
+                return Err(SourceError::EmptyField { line_no, field: name });
+            }
+            Ok(String::from(raw))
+        };
+        Ok(Self {
+            chembl_id: field(order.chembl_id, "chembl_id")?,
+            smiles: field(order.smiles, "smiles")?,
+            inchi: field(order.inchi, "inchi")?,
+            inchi_key: field(order.inchi_key, "inchi_key")?
+        })
+    }
+}
+
+/// Streams `EntryChembl` records one line at a time from a chemreps file
+/// without retaining them, so a multi-gigabyte ChEMBL dump can be scanned
+/// with bounded memory. Mirrors the indexed/streaming reader pattern used
+/// by rust-htslib's `bam` module: a thin wrapper around a `BufReader` that
+/// yields owned records lazily via `Iterator`.
+pub struct SourceChemblReader<R> {
+    lines: std::io::Lines<R>,
+    format_order: Option<(ChemblFormat, ColumnOrder)>,
+    line_no: usize
+}
+
+impl SourceChemblReader<Box<dyn std::io::BufRead>> {
+    /// Opens `filepath`, transparently decompressing it first if its
+    /// extension marks it as gzip- or xz-compressed, as ChEMBL dumps are
+    /// distributed from the EBI FTP site (`*_chemreps.txt.gz`).
+    ///
+    /// Panics if `filepath` can't be opened; use [`Self::try_new`] to
+    /// recover from that instead.
+    pub fn new(filepath: &std::path::Path) -> Self {
+        Self::try_new(filepath).unwrap()
+    }
+
+    pub fn try_new(filepath: &std::path::Path) -> Result<Self, SourceError> {
+        let file = std::fs::File::open(filepath)?;
+        let reader: Box<dyn std::io::BufRead> = match filepath.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Box::new(std::io::BufReader::new(flate2::read::GzDecoder::new(file))),
+            Some("xz") => Box::new(std::io::BufReader::new(xz2::read::XzDecoder::new(file))),
+            _ => Box::new(std::io::BufReader::new(file))
+        };
+        Ok(Self::from_reader(reader))
+    }
+}
+
+impl<R: std::io::BufRead> SourceChemblReader<R> {
+    /// Wraps an already-constructed reader, e.g. one decompressing on the fly.
+    pub fn from_reader(reader: R) -> Self {
+        Self { lines: reader.lines(), format_order: None, line_no: 0 }
+    }
+
+    /// Returns an iterator over the parsed records, skipping the header row.
+    pub fn records(self) -> impl Iterator<Item = Result<EntryChembl, SourceError>> {
+        self
+    }
+}
+
+impl<R: std::io::BufRead> Iterator for SourceChemblReader<R> {
+    type Item = Result<EntryChembl, SourceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(SourceError::Io(e)))
+            };
+            self.line_no += 1;
+
+            if self.format_order.is_none() {
+                let format = ChemblFormat::detect(&line);
+                let named_order = ColumnOrder::from_header(&line, format.delimiter());
+                let is_header = named_order.is_some();
+                let order = named_order.unwrap_or_else(ColumnOrder::default_chemreps);
+                self.format_order = Some((format, order));
+                if is_header {
+                    // The first line named its columns, so it's the header, not a record.
+                    continue;
+                }
+            }
+
+            let (format, order) = self.format_order.as_ref().unwrap();
+            let v = line.split(format.delimiter()).collect::<Vec<&str>>();
+            return Some(EntryChembl::try_new(&v, format, order, self.line_no));
+        }
     }
 }
 
 type DataChembl = std::collections::HashMap<String, EntryChembl>;
 
+/// Length of the connectivity block of an InChIKey, e.g. the
+/// `OPELSESCRGGKAM` in `OPELSESCRGGKAM-UHFFFAOYSA-N`. Entries sharing this
+/// skeleton are the same scaffold modulo stereochemistry.
+const INCHI_KEY_SKELETON_LEN: usize = 14;
+
 pub struct SourceChembl {
-    data: DataChembl 
+    data: DataChembl,
+    by_inchi_key: std::collections::HashMap<StandardInchiKey, ChemblID>,
+    by_smiles: std::collections::HashMap<CanonicalSMILES, ChemblID>
 }
 
 impl SourceChembl {
     pub fn new(filepath: &std::path::Path) -> Self {
-        let mut sc = Self { data: DataChembl::new() };
+        let mut sc = Self {
+            data: DataChembl::new(),
+            by_inchi_key: std::collections::HashMap::new(),
+            by_smiles: std::collections::HashMap::new()
+        };
         sc.load(filepath);
         sc
     }
@@ -60,29 +243,77 @@ impl SourceChembl {
         self.data.remove("chembl_id");
     }
 
+    fn index(&mut self) {
+        self.by_inchi_key.clear();
+        self.by_smiles.clear();
+        for ec in self.data.values() {
+            self.by_inchi_key.insert(ec.inchi_key.clone(), ec.chembl_id.clone());
+            self.by_smiles.insert(ec.smiles.clone(), ec.chembl_id.clone());
+        }
+    }
+
+    /// Loads `filepath`, panicking if it can't be opened or read. Malformed
+    /// rows are skipped; use [`Self::try_load`] to see what was skipped.
     pub fn load(&mut self, filepath: &std::path::Path) {
+        self.try_load(filepath).unwrap();
+    }
+
+    /// As [`Self::new`], but reporting a load failure instead of panicking.
+    pub fn try_new(filepath: &std::path::Path) -> Result<Self, SourceError> {
+        let mut sc = Self {
+            data: DataChembl::new(),
+            by_inchi_key: std::collections::HashMap::new(),
+            by_smiles: std::collections::HashMap::new()
+        };
+        sc.try_load(filepath)?;
+        Ok(sc)
+    }
+
+    /// Loads `filepath`, returning the malformed rows that were skipped
+    /// rather than panicking on them. Fails only on an underlying I/O error.
+    pub fn try_load(&mut self, filepath: &std::path::Path) -> Result<Vec<SourceError>, SourceError> {
         self.data.clear();
+        let mut skipped = Vec::new();
 
-        let file = std::fs::File::open(filepath).unwrap();
-        let reader = std::io::BufReader::new(file);
-        self.data = reader.lines()
-            .map(|l| {
-                    let line = l.unwrap();
-                    let v = line.as_str().split('\t').collect::<Vec<&str>>();
-                    (String::from(v[0]), EntryChembl::new(v))
-                }
-            )
-            .collect::<Vec<(ChemblID, EntryChembl)>>()
-            .into_iter()
-            .collect();
+        for record in SourceChemblReader::try_new(filepath)?.records() {
+            match record {
+                Ok(ec) => { self.data.insert(ec.chembl_id.clone(), ec); }
+                Err(SourceError::Io(e)) => return Err(SourceError::Io(e)),
+                Err(e) => skipped.push(e)
+            }
+        }
 
         self.sanitize();
+        self.index();
+        Ok(skipped)
     }
 
     pub fn get(&self, id: &ChemblID) -> Option<&EntryChembl> {
         self.data.get(id)
     }
 
+    pub fn get_by_inchi_key(&self, inchi_key: &str) -> Option<&EntryChembl> {
+        self.by_inchi_key.get(inchi_key).and_then(|id| self.get(id))
+    }
+
+    pub fn get_by_smiles(&self, smiles: &str) -> Option<&EntryChembl> {
+        self.by_smiles.get(smiles).and_then(|id| self.get(id))
+    }
+
+    /// Returns every entry whose InChIKey shares the 14-character
+    /// connectivity skeleton with `inchi_key_prefix`, i.e. the same
+    /// scaffold regardless of stereochemistry.
+    pub fn get_by_inchi_key_prefix(&self, inchi_key_prefix: &str) -> Vec<&EntryChembl> {
+        let end = inchi_key_prefix.char_indices()
+            .nth(INCHI_KEY_SKELETON_LEN)
+            .map(|(i, _)| i)
+            .unwrap_or(inchi_key_prefix.len());
+        let skeleton = &inchi_key_prefix[..end];
+        self.data.values()
+            .filter(|ec| ec.inchi_key.starts_with(skeleton))
+            .collect()
+    }
+
     pub fn get_all(&self) -> &DataChembl {
         &self.data
     }
@@ -102,23 +333,203 @@ impl SourceChembl {
         self.data.len()
     }
 
+    /// Returns a uniformly random sample of `min(size, len)` distinct entries.
     pub fn choices(&self, size: usize) -> Vec<&EntryChembl> {
-        let mut rng = thread_rng();
-        let marks: Vec<bool> = (0..self.len())
-            .map(|_| rng.gen_range(0..self.len()) <= size * 2 )
-            .collect();
+        self.choices_with_rng(size, &mut thread_rng())
+    }
 
-        self.data.values().enumerate()
-            .filter(|(idx, _)| marks[*idx])
-            .map(|(_, v)| v)
-            .take(size)
-            .collect()
+    /// As [`Self::choices`], but drawing from a caller-supplied RNG so the
+    /// sample is reproducible when seeded.
+    pub fn choices_with_rng(&self, size: usize, rng: &mut impl Rng) -> Vec<&EntryChembl> {
+        reservoir_sample(self.data.values(), size, rng)
+    }
+}
+
+/// Vitter's Algorithm R: draws a uniform, unbiased sample of `min(size, len)`
+/// items from `iter` in a single pass, without knowing the total count ahead
+/// of time. This is what makes it usable against the streaming
+/// `SourceChemblReader`, where the dataset size isn't known up front.
+pub fn reservoir_sample<T>(iter: impl Iterator<Item = T>, size: usize, rng: &mut impl Rng) -> Vec<T> {
+    let mut reservoir: Vec<T> = Vec::with_capacity(size);
+    for (i, item) in iter.enumerate() {
+        if i < size {
+            reservoir.push(item);
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < size {
+                reservoir[j] = item;
+            }
+        }
     }
+    reservoir
 }
 
 #[cfg(test)]
 mod test_chembl {
     use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static TEST_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Writes `content` to a uniquely-named file under the system temp dir
+    /// and returns its path, for tests that need to craft rows `SourceChembl`'s
+    /// fixed `./data` fixture can't (malformed rows, alternate formats, ...).
+    fn write_temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let n = TEST_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("chembl_test_{}_{n}_{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    const CHEMREPS_HEADER: &str = "chembl_id\tcanonical_smiles\tstandard_inchi\tstandard_inchi_key";
+
+    fn chemreps_row(id: &str) -> String {
+        format!("{id}\tCOc1ccccc1\tInChI=1S/C7H8O/c1-8-7-5-3-2-4-6-7/h2-6H,1H3\t{id}KEY-UHFFFAOYSA-N")
+    }
+
+    #[test]
+    fn test_reader_streams_records() {
+        let content = format!("{CHEMREPS_HEADER}\n{}\n{}\n", chemreps_row("CHEMBL1"), chemreps_row("CHEMBL2"));
+        let path = write_temp_file("streaming.txt", &content);
+
+        let records: Vec<EntryChembl> = SourceChemblReader::new(&path).records()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].chembl_id, "CHEMBL1");
+        assert_eq!(records[1].chembl_id, "CHEMBL2");
+    }
+
+    #[test]
+    fn test_reader_keeps_first_row_when_no_header_present() {
+        let content = format!("{}\n{}\n", chemreps_row("CHEMBL1"), chemreps_row("CHEMBL2"));
+        let path = write_temp_file("headerless.txt", &content);
+
+        let records: Vec<EntryChembl> = SourceChemblReader::new(&path).records()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].chembl_id, "CHEMBL1");
+    }
+
+    #[test]
+    fn test_try_load_skips_malformed_rows() {
+        let content = format!(
+            "{CHEMREPS_HEADER}\n\
+             {}\n\
+             CHEMBL_TRUNCATED\tCOc1ccccc1\n\
+             CHEMBL_EMPTY\t\tInChI=1S/C7H8O\tCHEMBL_EMPTYKEY-UHFFFAOYSA-N\n\
+             {}\n",
+            chemreps_row("CHEMBL1"), chemreps_row("CHEMBL2")
+        );
+        let path = write_temp_file("malformed.txt", &content);
+
+        let mut sc = SourceChembl::new(&path);
+        let skipped = sc.try_load(&path).unwrap();
+
+        assert_eq!(sc.len(), 2);
+        assert!(sc.get(&String::from("CHEMBL1")).is_some());
+        assert!(sc.get(&String::from("CHEMBL2")).is_some());
+        assert_eq!(skipped.len(), 2);
+        assert!(matches!(skipped[0], SourceError::MalformedRecord { .. }));
+        assert!(matches!(skipped[1], SourceError::EmptyField { .. }));
+    }
+
+    #[test]
+    fn test_csv_format_with_reordered_columns_is_detected() {
+        let content =
+            "standard_inchi_key,chembl_id,canonical_smiles,standard_inchi\n\
+             ABCDEFGHIJKLMN-UHFFFAOYSA-N,CHEMBL1,COc1ccccc1,InChI=1S/C7H8O\n";
+        let path = write_temp_file("reordered.csv", content);
+
+        let records: Vec<EntryChembl> = SourceChemblReader::new(&path).records()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].chembl_id, "CHEMBL1");
+        assert_eq!(records[0].smiles, "COc1ccccc1");
+        assert_eq!(records[0].inchi_key, "ABCDEFGHIJKLMN-UHFFFAOYSA-N");
+    }
+
+    #[test]
+    fn test_choices_with_rng_is_reproducible_and_bounded() {
+        let rows: String = (0..20).map(|i| format!("{}\n", chemreps_row(&format!("CHEMBL{i}")))).collect();
+        let content = format!("{CHEMREPS_HEADER}\n{rows}");
+        let path = write_temp_file("reservoir.txt", &content);
+        let sc = SourceChembl::new(&path);
+        assert_eq!(sc.len(), 20);
+
+        let mut rng_a = rand::rngs::StdRng::seed_from_u64(42);
+        let sample_a: Vec<&str> = sc.choices_with_rng(5, &mut rng_a).iter().map(|ec| ec.chembl_id.as_str()).collect();
+
+        let mut rng_b = rand::rngs::StdRng::seed_from_u64(42);
+        let sample_b: Vec<&str> = sc.choices_with_rng(5, &mut rng_b).iter().map(|ec| ec.chembl_id.as_str()).collect();
+
+        assert_eq!(sample_a, sample_b);
+        assert_eq!(sample_a.len(), 5);
+
+        let mut rng_c = rand::rngs::StdRng::seed_from_u64(42);
+        let oversized = sc.choices_with_rng(1000, &mut rng_c);
+        assert_eq!(oversized.len(), sc.len());
+    }
+
+    #[test]
+    fn test_reader_decompresses_gzip() {
+        let content = format!("{CHEMREPS_HEADER}\n{}\n", chemreps_row("CHEMBL1"));
+        let path = write_temp_file("compressed.txt.gz", "");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let records: Vec<EntryChembl> = SourceChemblReader::new(&path).records()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].chembl_id, "CHEMBL1");
+    }
+
+    #[test]
+    fn test_reader_decompresses_xz() {
+        let content = format!("{CHEMREPS_HEADER}\n{}\n", chemreps_row("CHEMBL1"));
+        let path = write_temp_file("compressed.txt.xz", "");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut encoder = xz2::write::XzEncoder::new(file, 6);
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let records: Vec<EntryChembl> = SourceChemblReader::new(&path).records()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].chembl_id, "CHEMBL1");
+    }
+
+    #[test]
+    fn test_secondary_indices() {
+        let content = format!(
+            "{CHEMREPS_HEADER}\n\
+             CHEMBL1\tCOc1ccccc1\tInChI=1S/C7H8O\tABCDEFGHIJKLMN-UHFFFAOYSA-N\n\
+             CHEMBL2\tCCO\tInChI=1S/C2H6O\tABCDEFGHIJKLMN-UHFFFAOYSB-N\n\
+             CHEMBL3\tCCN\tInChI=1S/C2H7N\tZZZZZZZZZZZZZZ-UHFFFAOYSA-N\n"
+        );
+        let path = write_temp_file("secondary_indices.txt", &content);
+        let sc = SourceChembl::new(&path);
+
+        assert_eq!(sc.get_by_inchi_key("ABCDEFGHIJKLMN-UHFFFAOYSA-N").unwrap().chembl_id, "CHEMBL1");
+        assert_eq!(sc.get_by_smiles("CCO").unwrap().chembl_id, "CHEMBL2");
+        assert!(sc.get_by_smiles("nonexistent").is_none());
+
+        let mut skeleton_matches: Vec<&str> = sc.get_by_inchi_key_prefix("ABCDEFGHIJKLMN-UHFFFAOYSA-N")
+            .iter().map(|ec| ec.chembl_id.as_str()).collect();
+        skeleton_matches.sort();
+        assert_eq!(skeleton_matches, vec!["CHEMBL1", "CHEMBL2"]);
+    }
 
     #[test]
     fn test_source_chembl() {
@@ -0,0 +1,130 @@
+//! Persistent, embedded on-disk index for ChEMBL.
+//!
+//! `SourceChembl` keeps the whole file resident in a `HashMap`, which means
+//! every process start pays the cost of re-parsing a multi-gigabyte
+//! chemreps dump. `SourceChemblDb` instead ingests the text file into a
+//! [sled](https://docs.rs/sled) embedded key-value store once, keyed by
+//! ChEMBL ID, and on later opens serves lookups straight from disk.
+
+use crate::chembl::{ChemblID, EntryChembl, SourceChemblReader, SourceError};
+
+/// Errors that can occur while opening or querying a `SourceChemblDb`.
+#[derive(Debug)]
+pub enum DbError {
+    Sled(sled::Error),
+    Source(SourceError),
+    Codec(bincode::Error)
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DbError::Sled(e) => write!(f, "sled error: {e}"),
+            DbError::Source(e) => write!(f, "source error: {e}"),
+            DbError::Codec(e) => write!(f, "codec error: {e}")
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<sled::Error> for DbError {
+    fn from(e: sled::Error) -> Self {
+        DbError::Sled(e)
+    }
+}
+
+impl From<SourceError> for DbError {
+    fn from(e: SourceError) -> Self {
+        DbError::Source(e)
+    }
+}
+
+impl From<bincode::Error> for DbError {
+    fn from(e: bincode::Error) -> Self {
+        DbError::Codec(e)
+    }
+}
+
+pub struct SourceChemblDb {
+    db: sled::Db
+}
+
+impl SourceChemblDb {
+    /// Opens (and, if empty, ingests `source_path` into) the on-disk store
+    /// at `db_path`. A store that has already been ingested is served
+    /// directly from disk without touching `source_path` again.
+    pub fn open(db_path: &std::path::Path, source_path: &std::path::Path) -> Result<Self, DbError> {
+        let db = sled::open(db_path)?;
+        if db.is_empty() {
+            // Malformed rows are skipped here just as SourceChembl::load skips them.
+            for record in SourceChemblReader::try_new(source_path)?.records().filter_map(Result::ok) {
+                let value = bincode::serialize(&record)?;
+                db.insert(record.chembl_id.as_bytes(), value)?;
+            }
+            db.flush()?;
+        }
+        Ok(Self { db })
+    }
+
+    pub fn get(&self, id: &ChemblID) -> Result<Option<EntryChembl>, DbError> {
+        match self.db.get(id.as_bytes())? {
+            Some(v) => Ok(Some(bincode::deserialize(&v)?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn get_all(&self) -> Result<Vec<EntryChembl>, DbError> {
+        self.db.iter()
+            .values()
+            .map(|v| -> Result<EntryChembl, DbError> {
+                Ok(bincode::deserialize(&v?)?)
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.db.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test_chembl_db {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static TEST_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let n = TEST_DIR_COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("chembl_db_test_{}_{n}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_open_ingests_then_serves_from_disk() {
+        let source_path = temp_path("source.txt");
+        std::fs::write(&source_path,
+            "chembl_id\tcanonical_smiles\tstandard_inchi\tstandard_inchi_key\n\
+             CHEMBL1\tCOc1ccccc1\tInChI=1S/C7H8O/c1-8-7-5-3-2-4-6-7/h2-6H,1H3\tCHEMBL1KEY-UHFFFAOYSA-N\n"
+        ).unwrap();
+        let db_path = temp_path("db");
+
+        let db = SourceChemblDb::open(&db_path, &source_path).unwrap();
+        assert_eq!(db.len(), 1);
+        assert!(!db.is_empty());
+        let ec = db.get(&String::from("CHEMBL1")).unwrap().unwrap();
+        assert_eq!(ec.smiles, "COc1ccccc1");
+
+        // Re-opening against an already-ingested store must not touch source_path again.
+        // sled holds an exclusive lock on the store, so the first handle is closed first.
+        drop(db);
+        std::fs::remove_file(&source_path).unwrap();
+        let reopened = SourceChemblDb::open(&db_path, &source_path).unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(reopened.get_all().unwrap().len(), 1);
+    }
+}